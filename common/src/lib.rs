@@ -0,0 +1,86 @@
+mod rabbit;
+mod tls;
+
+pub use rabbit::{
+    confirm_select, connect, declare_queue_with_dlx, parse_headers, publish, requeue_with_retry,
+    retry_count, routing_key,
+};
+pub use tls::TlsConfig;
+
+use {anyhow::Context, serde::Deserialize, std::path::PathBuf};
+
+/// Shared connection settings for every playground binary, loaded from the
+/// environment (and `.env`, via `dotenv`) with serde instead of each binary
+/// re-declaring the same handful of clap `env = "..."` args.
+///
+/// CLI flags still take precedence where a binary exposes one: load a
+/// `Config` first, then let the binary's own `Cli` override individual
+/// fields before use.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default = "default_amqp_addr")]
+    pub amqp_addr: String,
+    #[serde(default)]
+    pub exchange: Option<String>,
+    #[serde(default)]
+    pub routing_key: Option<String>,
+    #[serde(default)]
+    pub queue: Option<String>,
+    #[serde(default = "default_prefetch")]
+    pub prefetch: u16,
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    #[serde(default)]
+    pub ca_cert: Option<PathBuf>,
+    #[serde(default)]
+    pub client_cert: Option<PathBuf>,
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+}
+
+fn default_amqp_addr() -> String {
+    String::from("amqp://localhost:5672")
+}
+
+fn default_prefetch() -> u16 {
+    10
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+impl Config {
+    /// Loads configuration from the environment, merging in a `.env` file
+    /// first if one is present.
+    ///
+    /// Validates the AMQP URL scheme up front so a typo'd address produces
+    /// a clear error here rather than a panic inside `Connection::connect`.
+    pub fn load() -> anyhow::Result<Config> {
+        dotenv::dotenv().ok();
+
+        let config: Config = envy::from_env().context("failed to load configuration from the environment")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The TLS material to use when connecting, as configured (before any
+    /// CLI overrides the calling binary wants to layer on top).
+    pub fn tls(&self) -> TlsConfig {
+        TlsConfig {
+            ca_cert: self.ca_cert.to_owned(),
+            client_cert: self.client_cert.to_owned(),
+            client_key: self.client_key.to_owned(),
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        match self.amqp_addr.split_once("://").map(|(scheme, _)| scheme) {
+            Some("amqp") | Some("amqps") => Ok(()),
+            _ => Err(anyhow::anyhow!(
+                "unsupported AMQP URL `{}`, expected an amqp:// or amqps:// scheme",
+                self.amqp_addr
+            )),
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use {
+    crate::TlsConfig,
+    anyhow::Context,
+    lapin::{
+        message::Delivery,
+        options::{BasicAckOptions, BasicPublishOptions, ConfirmSelectOptions, QueueDeclareOptions},
+        types::{AMQPValue, FieldTable},
+        BasicProperties, Channel, Connection, ConnectionProperties,
+    },
+    std::time::{SystemTime, UNIX_EPOCH},
+    tracing::{debug, warn},
+    uuid::Uuid,
+};
+
+/// Header we stamp on a requeued delivery to count how many times it has
+/// already been retried. `x-death` only appears once a message has
+/// actually passed through a dead-letter exchange, which never happens for
+/// a plain `nack(requeue: true)`; tracking our own header lets a retry
+/// loop that never touches the DLX still get bounded.
+pub const RETRY_COUNT_HEADER: &str = "x-retry-count";
+
+/// Header we stamp with a delivery's original routing key the first time
+/// it's retried. [`requeue_with_retry`] republishes via the default
+/// exchange using the queue name as the routing key, which would otherwise
+/// overwrite `delivery.routing_key` and break [`routing_key`] callers (and
+/// any eventual dead-lettering) after the first retry.
+pub const ORIGINAL_ROUTING_KEY_HEADER: &str = "x-original-routing-key";
+
+/// Connects to `addr` and opens a channel on it, returning an error instead
+/// of panicking on failure so callers can decide how to report it.
+pub async fn connect(
+    addr: &str,
+    options: ConnectionProperties,
+    tls: &TlsConfig,
+) -> anyhow::Result<(Connection, Channel)> {
+    let connection = crate::tls::connect(addr, options, tls)
+        .await
+        .context("failed to connect to RabbitMQ")?;
+    debug!(target="connection", state=?connection.status().state());
+
+    let channel = connection
+        .create_channel()
+        .await
+        .context("failed to create channel")?;
+    debug!(target="channel", state=?channel.status().state());
+
+    Ok((connection, channel))
+}
+
+/// Puts `channel` into publisher-confirm mode.
+pub async fn confirm_select(channel: &Channel) -> anyhow::Result<()> {
+    channel
+        .confirm_select(ConfirmSelectOptions::default())
+        .await
+        .context("failed to enable publisher confirms")?;
+    debug!(target="channel", state=?channel.status().state());
+    Ok(())
+}
+
+/// Parses `key=value` header pairs, logging and skipping any entry that
+/// isn't in that form (missing `=`, or anything split can't pair up).
+pub fn parse_headers(headers: &[String]) -> FieldTable {
+    headers.iter().fold(FieldTable::default(), |mut ft, s| {
+        let mut parts = s.split('=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => {
+                debug!("Adding header {} = {}", key, value);
+                ft.insert(key.into(), AMQPValue::LongString(value.into()))
+            }
+            _ => warn!("Ignoring unparsable header value '{}'!", s),
+        };
+        ft
+    })
+}
+
+/// Publishes `payload` with publisher confirms, stamping `message_id` and
+/// the optional `correlation_id` on top of the base `properties`. Also
+/// stamps the current time as the `timestamp`, unless `properties` already
+/// carries one (e.g. a replayed message restoring its original timestamp).
+/// Returns whether the broker confirmed the message.
+pub async fn publish(
+    channel: &Channel,
+    exchange: &str,
+    routing_key: &str,
+    properties: &BasicProperties,
+    correlation_id: &Option<String>,
+    payload: &[u8],
+) -> anyhow::Result<bool> {
+    let message_id = Uuid::new_v4().to_string();
+    let mut message_properties = properties.to_owned().with_message_id(message_id.into());
+    if properties.timestamp().is_none() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("system time before unix epoch")?
+            .as_secs();
+        message_properties = message_properties.with_timestamp(timestamp);
+    }
+    if let Some(correlation_id) = correlation_id {
+        message_properties = message_properties.with_correlation_id(correlation_id.to_owned().into());
+    }
+
+    let confirm = channel
+        .basic_publish(
+            exchange,
+            routing_key,
+            BasicPublishOptions {
+                mandatory: true,
+                ..BasicPublishOptions::default()
+            },
+            payload,
+            message_properties,
+        )
+        .await
+        .context("Basic Publish failure!")?
+        .await
+        .context("Published Confirm failure!")?;
+
+    if confirm.is_ack() {
+        if let Some(message) = confirm.take_message() {
+            warn!(
+                "Messaage rejected with {} {}",
+                message.reply_code, message.reply_text
+            );
+            Ok(false)
+        } else {
+            debug!("Message accepted");
+            Ok(true)
+        }
+    } else if confirm.is_nack() {
+        warn!("Message not acknowled!");
+        Ok(false)
+    } else {
+        warn!("Unknown message state!");
+        Ok(false)
+    }
+}
+
+/// Declares `queue`, wiring in a dead-letter exchange (and optional
+/// dead-letter routing key) when one is given.
+pub async fn declare_queue_with_dlx(
+    channel: &Channel,
+    queue: &str,
+    dlx: &Option<String>,
+    dlx_routing_key: &Option<String>,
+) -> anyhow::Result<()> {
+    let mut queue_args = FieldTable::default();
+    if let Some(dlx) = dlx {
+        queue_args.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString(dlx.to_owned().into()),
+        );
+        if let Some(dlx_routing_key) = dlx_routing_key {
+            queue_args.insert(
+                "x-dead-letter-routing-key".into(),
+                AMQPValue::LongString(dlx_routing_key.to_owned().into()),
+            );
+        }
+    }
+
+    channel
+        .queue_declare(
+            queue,
+            QueueDeclareOptions {
+                durable: true,
+                ..QueueDeclareOptions::default()
+            },
+            queue_args,
+        )
+        .await
+        .context("failed to declare queue")?;
+    Ok(())
+}
+
+/// How many times this delivery has already been retried, read from the
+/// [`RETRY_COUNT_HEADER`] stamped by [`requeue_with_retry`]. Absent (i.e.
+/// `0`) on a message's first delivery.
+pub fn retry_count(delivery: &Delivery) -> u64 {
+    delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(RETRY_COUNT_HEADER))
+        .and_then(AMQPValue::as_long_long_int)
+        .map(|count| count as u64)
+        .unwrap_or(0)
+}
+
+/// The routing key this delivery was originally published with, preferring
+/// the [`ORIGINAL_ROUTING_KEY_HEADER`] stamped by [`requeue_with_retry`]
+/// over `delivery.routing_key` once present, since a retried delivery's
+/// `routing_key` is overwritten with the queue name by the default-exchange
+/// republish.
+pub fn routing_key(delivery: &Delivery) -> String {
+    delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(ORIGINAL_ROUTING_KEY_HEADER))
+        .and_then(AMQPValue::as_long_string)
+        .map(|key| key.to_string())
+        .unwrap_or_else(|| delivery.routing_key.as_str().to_owned())
+}
+
+/// Retries `delivery` by republishing it to `queue` via the default
+/// exchange with [`RETRY_COUNT_HEADER`] set to `retry_count`, then
+/// acknowledges the original so it isn't also redelivered by the broker.
+/// Stashes the delivery's original routing key (see [`routing_key`]) the
+/// first time it's retried, since the default-exchange republish uses
+/// `queue` as the routing key and would otherwise clobber it.
+///
+/// A plain `nack(requeue: true)` redelivers the message unchanged on the
+/// same queue, with no way to stamp a retry count on it; republishing is
+/// the only way to carry that count (and the original routing key) forward
+/// between attempts.
+pub async fn requeue_with_retry(
+    channel: &Channel,
+    queue: &str,
+    delivery: &Delivery,
+    retry_count: u64,
+) -> anyhow::Result<()> {
+    let mut headers = delivery.properties.headers().to_owned().unwrap_or_default();
+    headers.insert(
+        RETRY_COUNT_HEADER.into(),
+        AMQPValue::LongLongInt(retry_count as i64),
+    );
+    if headers.inner().get(ORIGINAL_ROUTING_KEY_HEADER).is_none() {
+        headers.insert(
+            ORIGINAL_ROUTING_KEY_HEADER.into(),
+            AMQPValue::LongString(delivery.routing_key.as_str().into()),
+        );
+    }
+    let properties = delivery.properties.to_owned().with_headers(headers);
+
+    channel
+        .basic_publish(
+            "",
+            queue,
+            BasicPublishOptions::default(),
+            &delivery.data,
+            properties,
+        )
+        .await
+        .context("failed to requeue delivery with incremented retry count")?
+        .await
+        .context("requeue publish confirm failure")?;
+
+    delivery
+        .ack(BasicAckOptions::default())
+        .await
+        .context("failed to ack original delivery after requeuing")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_key_value_pairs() {
+        let headers = parse_headers(&[String::from("key1=value1"), String::from("key2=value2")]);
+
+        assert_eq!(
+            headers.inner().get("key1").and_then(AMQPValue::as_long_string),
+            Some(&"value1".into())
+        );
+        assert_eq!(
+            headers.inner().get("key2").and_then(AMQPValue::as_long_string),
+            Some(&"value2".into())
+        );
+    }
+
+    #[test]
+    fn ignores_entries_missing_an_equals() {
+        let headers = parse_headers(&[String::from("no-equals-sign")]);
+
+        assert!(headers.inner().is_empty());
+    }
+
+    #[test]
+    fn accepts_an_empty_value() {
+        let headers = parse_headers(&[String::from("key=")]);
+
+        assert_eq!(
+            headers.inner().get("key").and_then(AMQPValue::as_long_string),
+            Some(&"".into())
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use {
+    lapin::{
+        tcp::{OwnedIdentity, OwnedTLSConfig},
+        Connection, ConnectionProperties,
+    },
+    std::{fs, path::PathBuf},
+};
+
+/// TLS material for connecting to an `amqps://` broker.
+///
+/// All three fields are optional: with none set, an `amqps://` address
+/// still connects TLS-secured using the platform's native certificate
+/// roots (via rustls); set `ca_cert` to additionally trust a private CA,
+/// and both `client_cert`/`client_key` together to present a client
+/// certificate for mutual TLS.
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    pub ca_cert: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    fn owned_config(&self) -> anyhow::Result<OwnedTLSConfig> {
+        let cert_chain = self.ca_cert.as_ref().map(fs::read_to_string).transpose()?;
+        let identity = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Some(OwnedIdentity {
+                cert: fs::read_to_string(cert)?,
+                key: fs::read_to_string(key)?,
+            }),
+            (None, None) => None,
+            _ => anyhow::bail!("--client-cert and --client-key must be given together"),
+        };
+        Ok(OwnedTLSConfig {
+            cert_chain,
+            identity,
+        })
+    }
+}
+
+/// Connects to `addr`, negotiating TLS for `amqps://` URLs and falling
+/// back to the plain `amqp://` connect otherwise.
+pub(crate) async fn connect(
+    addr: &str,
+    options: ConnectionProperties,
+    tls: &TlsConfig,
+) -> anyhow::Result<Connection> {
+    let connection = if addr.starts_with("amqps://") {
+        Connection::connect_with_config(addr, options, tls.owned_config()?).await?
+    } else {
+        Connection::connect(addr, options).await?
+    };
+    Ok(connection)
+}
@@ -0,0 +1,74 @@
+//! Publishes a batch of messages through a throwaway RabbitMQ broker and
+//! asserts a consumer receives and acks every one of them. Requires Docker;
+//! ignored by default so `cargo test` stays usable offline.
+use {
+    common::TlsConfig,
+    lapin::{
+        options::{BasicAckOptions, BasicConsumeOptions, QueueDeclareOptions},
+        types::FieldTable,
+        ConnectionProperties,
+    },
+    testcontainers::{clients::Cli, RunnableImage},
+    testcontainers_modules::rabbitmq::RabbitMq,
+    tokio_stream::StreamExt,
+};
+
+#[tokio::test]
+#[ignore = "spins up a RabbitMQ container; run with `cargo test -- --ignored`"]
+async fn publishes_and_consumes_a_batch() {
+    let docker = Cli::default();
+    let broker = docker.run(RunnableImage::from(RabbitMq::default()));
+    let addr = format!(
+        "amqp://127.0.0.1:{}/%2f",
+        broker.get_host_port_ipv4(5672)
+    );
+
+    let (_connection, channel) = common::connect(
+        &addr,
+        ConnectionProperties::default(),
+        &TlsConfig::default(),
+    )
+    .await
+    .expect("failed to connect to the test broker");
+    common::confirm_select(&channel)
+        .await
+        .expect("failed to enable publisher confirms");
+
+    let queue = "integration-test-queue";
+    channel
+        .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+        .await
+        .expect("failed to declare queue");
+
+    let properties = lapin::BasicProperties::default();
+    let batch = ["one", "two", "three"];
+    for payload in batch {
+        let confirmed = common::publish(&channel, "", queue, &properties, &None, payload.as_bytes())
+            .await
+            .expect("publish failed");
+        assert!(confirmed, "message `{}` was not confirmed", payload);
+    }
+
+    let mut consumer = channel
+        .basic_consume(
+            queue,
+            "integration-test-consumer",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await
+        .expect("failed to start consuming");
+
+    for expected in batch {
+        let delivery = consumer
+            .next()
+            .await
+            .expect("consumer stream ended early")
+            .expect("delivery error");
+        assert_eq!(delivery.data, expected.as_bytes());
+        delivery
+            .ack(BasicAckOptions::default())
+            .await
+            .expect("ack failed");
+    }
+}
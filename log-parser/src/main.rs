@@ -1,13 +1,20 @@
-use std::io::BufRead;
+mod record;
 
-#[allow(unused_imports)]
 use {
     anyhow::{Context, Result},
     clap::Parser,
+    common::Config,
     dotenv::dotenv,
-    // requestty::{prompt_one, Answer, Question},
-    std::{fs::File, io::BufReader, path::PathBuf},
-    tracing::{debug, info},
+    lapin::{BasicProperties, ConnectionProperties},
+    record::{parse_pattern, parse_prefix, LogRecord},
+    regex::Regex,
+    std::{
+        fs::File,
+        io::{BufRead, BufReader},
+        path::PathBuf,
+    },
+    tokio::runtime::Builder,
+    tracing::{debug, info, warn},
 };
 
 #[derive(Parser, Debug)]
@@ -15,6 +22,95 @@ struct Cli {
     /// The log file to process
     #[arg(name = "Log File", long, short, env = "LOG_FILE")]
     path: Option<PathBuf>,
+    /// Length of the fixed-width prefix (timestamp + routing key) to strip
+    /// from each line; ignored if --pattern is given
+    #[arg(long, default_value_t = 33)]
+    prefix_len: usize,
+    /// Regex used instead of --prefix-len, with a required `payload`
+    /// capture group and optional `timestamp`/`routing_key` ones
+    #[arg(long)]
+    pattern: Option<String>,
+    /// Republish parsed payloads to this exchange instead of printing them
+    #[arg(name = "replay-to", long)]
+    replay_to: Option<String>,
+    /// Routing key used when replaying a record with no routing key of its
+    /// own, overrides the `routing_key` config
+    #[arg(name = "routing-key", long, short)]
+    routing_key: Option<String>,
+}
+
+fn parse_lines(path: &PathBuf, cli: &Cli) -> Result<Vec<LogRecord>> {
+    let file = File::open(path).with_context(|| format!("could not open file `{:?}`", path))?;
+    let buffer = BufReader::new(file);
+    let pattern = cli
+        .pattern
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .context("invalid --pattern regex")?;
+
+    Ok(buffer
+        .lines()
+        .filter_map(|line| line.ok())
+        .filter(|line| !line.starts_with("--"))
+        .filter_map(|line| {
+            let record = match &pattern {
+                Some(pattern) => parse_pattern(&line, pattern),
+                None => parse_prefix(&line, cli.prefix_len),
+            };
+            if record.is_none() {
+                warn!("Skipping malformed log line: {:?}", line);
+            }
+            record
+        })
+        .collect())
+}
+
+/// Republishes each parsed `records` entry to `exchange`, restoring its
+/// original timestamp and falling back to `default_routing_key` when a
+/// record didn't carry its own.
+fn replay(
+    config: &Config,
+    exchange: &str,
+    default_routing_key: &str,
+    records: Vec<LogRecord>,
+) -> Result<()> {
+    let runtime = Builder::new_current_thread().enable_all().build()?;
+    let tls = config.tls();
+
+    let (_connection, channel) = runtime.block_on(async {
+        let (connection, channel) =
+            common::connect(&config.amqp_addr, ConnectionProperties::default(), &tls)
+                .await
+                .context("Create connection failure!")?;
+        common::confirm_select(&channel)
+            .await
+            .context("Confirm select failure!")?;
+        Ok::<_, anyhow::Error>((connection, channel))
+    })?;
+
+    for record in records {
+        let routing_key = record.routing_key.as_deref().unwrap_or(default_routing_key);
+        let properties = match record.timestamp {
+            Some(timestamp) => BasicProperties::default().with_timestamp(timestamp),
+            None => BasicProperties::default(),
+        };
+        let confirmed = runtime.block_on(common::publish(
+            &channel,
+            exchange,
+            routing_key,
+            &properties,
+            &None,
+            record.payload.as_bytes(),
+        ))?;
+        if confirmed {
+            debug!("Replayed > {}", record.payload);
+        } else {
+            warn!("Replay not acknowledged for > {}", record.payload);
+        }
+    }
+
+    Ok(())
 }
 
 fn main() -> Result<()> {
@@ -27,18 +123,23 @@ fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
     let cli = Cli::parse();
+    let config = Config::load()?;
 
-    let path = &cli.path.unwrap_or("data/test.log".into());
+    let path = cli.path.clone().unwrap_or_else(|| "data/test.log".into());
     info!("Using {}", path.display());
-    let file = File::open(path).with_context(|| format!("could not open file `{:?}`", path))?;
-    let buffer = BufReader::new(file);
+    let records = parse_lines(&path, &cli)?;
 
-    buffer
-        .lines()
-        .filter_map(|s| s.ok())
-        .map(|s| String::from(&s[33..]))
-        .filter(|s| !s.starts_with("--"))
-        .for_each(|s| println!("{}", s));
+    match &cli.replay_to {
+        Some(exchange) => {
+            let routing_key = cli
+                .routing_key
+                .clone()
+                .or_else(|| config.routing_key.clone())
+                .unwrap_or_default();
+            replay(&config, exchange, &routing_key, records)?;
+        }
+        None => records.iter().for_each(|record| println!("{}", record.payload)),
+    }
 
     Ok(())
 }
@@ -0,0 +1,90 @@
+use regex::Regex;
+
+/// A log line parsed into the pieces needed to replay it onto RabbitMQ: the
+/// original send time, the routing key it went out under (if known), and
+/// the payload that follows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub timestamp: Option<u64>,
+    pub routing_key: Option<String>,
+    pub payload: String,
+}
+
+/// Parses `line` using a fixed-width prefix of `prefix_len` bytes, assumed
+/// to hold `<unix timestamp> <routing key>` separated by whitespace.
+/// Returns `None` (rather than panicking on an out-of-bounds slice) when
+/// the line is shorter than the prefix, or when `prefix_len` lands in the
+/// middle of a multi-byte character.
+pub fn parse_prefix(line: &str, prefix_len: usize) -> Option<LogRecord> {
+    if line.len() < prefix_len || !line.is_char_boundary(prefix_len) {
+        return None;
+    }
+    let (prefix, payload) = line.split_at(prefix_len);
+    let mut fields = prefix.split_whitespace();
+    let timestamp = fields.next().and_then(|field| field.parse().ok());
+    let routing_key = fields.next().map(String::from);
+    Some(LogRecord {
+        timestamp,
+        routing_key,
+        payload: payload.to_string(),
+    })
+}
+
+/// Parses `line` with `pattern`, which must have a `payload` capture group
+/// and may have `timestamp`/`routing_key` ones. Returns `None` if the line
+/// doesn't match or is missing the required `payload` group.
+pub fn parse_pattern(line: &str, pattern: &Regex) -> Option<LogRecord> {
+    let captures = pattern.captures(line)?;
+    let timestamp = captures
+        .name("timestamp")
+        .and_then(|field| field.as_str().parse().ok());
+    let routing_key = captures.name("routing_key").map(|field| field.as_str().to_string());
+    let payload = captures.name("payload")?.as_str().to_string();
+    Some(LogRecord {
+        timestamp,
+        routing_key,
+        payload,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_well_formed_prefix() {
+        let record = parse_prefix("1700000000 greeting              Hello!", 33).unwrap();
+
+        assert_eq!(record.timestamp, Some(1700000000));
+        assert_eq!(record.routing_key, Some(String::from("greeting")));
+        assert_eq!(record.payload, "Hello!");
+    }
+
+    #[test]
+    fn skips_lines_shorter_than_the_prefix() {
+        assert_eq!(parse_prefix("too short", 33), None);
+    }
+
+    #[test]
+    fn skips_lines_where_the_prefix_splits_a_multibyte_character() {
+        // "é" is 2 bytes, so byte 10 lands in the middle of it.
+        assert_eq!(parse_prefix("123456789é rest", 10), None);
+    }
+
+    #[test]
+    fn parses_with_a_custom_pattern() {
+        let pattern = Regex::new(r"^(?P<timestamp>\d+)\|(?P<routing_key>[^|]+)\|(?P<payload>.*)$").unwrap();
+        let record = parse_pattern("1700000000|greeting|Hello!", &pattern).unwrap();
+
+        assert_eq!(record.timestamp, Some(1700000000));
+        assert_eq!(record.routing_key, Some(String::from("greeting")));
+        assert_eq!(record.payload, "Hello!");
+    }
+
+    #[test]
+    fn pattern_without_a_payload_group_does_not_match() {
+        let pattern = Regex::new(r"^(?P<timestamp>\d+) (?P<routing_key>.+)$").unwrap();
+
+        assert_eq!(parse_pattern("1700000000 greeting", &pattern), None);
+    }
+}
@@ -1,77 +1,97 @@
 use {
+    anyhow::Context,
     clap::Parser,
+    common::Config,
     dotenv::dotenv,
-    lapin::{
-        options::{BasicPublishOptions, ConfirmSelectOptions},
-        types::{AMQPValue, FieldTable},
-        BasicProperties, Connection, ConnectionProperties, Result,
-    },
+    lapin::{types::FieldTable, BasicProperties, ConnectionProperties},
     requestty::{prompt_one, Answer, Question},
-    std::iter::Iterator,
+    std::{
+        fs,
+        io::{self, BufRead},
+        iter::Iterator,
+        path::PathBuf,
+    },
     tokio::runtime::Builder,
     tracing::{debug, info, warn},
 };
 
 #[derive(Parser, Debug)]
 struct Cli {
-    /// URL of the RabbitMQ server
-    #[arg(
-        name = "url",
-        long,
-        short,
-        env = "AMQP_ADDR",
-        default_value_t = String::from("amqp://localhost:5672")
-    )]
-    url: String,
-    /// Exchange to publish to
-    #[arg(name = "exchange", long, short = 'x', env = "PUB_EXCHANGE")]
-    exchange: String,
-    /// The routing key to use
-    #[arg(name = "routing-key", long, short, env = "PUB_ROUTING")]
+    /// URL of the RabbitMQ server, overrides the AMQP_ADDR config
+    #[arg(name = "url", long, short)]
+    url: Option<String>,
+    /// Exchange to publish to, overrides the `exchange` config
+    #[arg(name = "exchange", long, short = 'x')]
+    exchange: Option<String>,
+    /// The routing key to use, overrides the `routing_key` config
+    #[arg(name = "routing-key", long, short)]
     routing_key: Option<String>,
     /// Optional message headers to send (format "key1=value1,key2=value2...")
-    #[arg(
-        name = "headers",
-        long,
-        short = 'p',
-        value_delimiter = ',',
-        env = "PUB_HEADERS"
-    )]
+    #[arg(name = "headers", long, short = 'p', value_delimiter = ',')]
     headers: Option<Vec<String>>,
+    /// Correlation id to stamp on every published message
+    #[arg(name = "correlation-id", long, short = 'c')]
+    correlation_id: Option<String>,
+    /// PEM file of a custom CA to trust for amqps:// connections, overrides
+    /// the `ca_cert` config
+    #[arg(name = "ca-cert", long)]
+    ca_cert: Option<PathBuf>,
+    /// Client certificate PEM for mutual TLS, overrides the `client_cert`
+    /// config; requires --client-key
+    #[arg(name = "client-cert", long)]
+    client_cert: Option<PathBuf>,
+    /// Client private key PEM for mutual TLS, overrides the `client_key`
+    /// config; requires --client-cert
+    #[arg(name = "client-key", long)]
+    client_key: Option<PathBuf>,
+    /// Literal message body to publish once, instead of the interactive demo
+    #[arg(name = "body", long, conflicts_with_all = ["body_file", "stdin"])]
+    body: Option<String>,
+    /// File whose contents are published once as the message body
+    #[arg(long, conflicts_with_all = ["body", "stdin"])]
+    body_file: Option<PathBuf>,
+    /// Read lines from stdin, publishing one message per line
+    #[arg(long, conflicts_with_all = ["body", "body_file"])]
+    stdin: bool,
+    /// Content-Type to stamp on published messages, e.g. application/json
+    #[arg(name = "content-type", long)]
+    content_type: Option<String>,
 }
 
 impl Cli {
-    pub fn url(&self) -> String {
-        self.url.to_owned()
+    pub fn url(&self, config: &Config) -> String {
+        self.url.to_owned().unwrap_or_else(|| config.amqp_addr.to_owned())
     }
 
-    pub fn exchange(&self) -> String {
-        self.exchange.to_owned()
+    pub fn exchange(&self, config: &Config) -> anyhow::Result<String> {
+        self.exchange
+            .to_owned()
+            .or_else(|| config.exchange.to_owned())
+            .context("no exchange given; pass --exchange or set the `exchange` config")
     }
 
-    pub fn routing_key(&self) -> String {
-        self.routing_key.to_owned().unwrap_or_default()
+    pub fn routing_key(&self, config: &Config) -> String {
+        self.routing_key
+            .to_owned()
+            .or_else(|| config.routing_key.to_owned())
+            .unwrap_or_default()
     }
 
-    pub fn headers(&self) -> Option<FieldTable> {
-        match self.headers.as_ref() {
-            Some(headers) => Some(headers.iter().fold(FieldTable::default(), |mut ft, s| {
-                let mut parts = s.split('=');
-                match (parts.next(), parts.next()) {
-                    (Some(key), Some(value)) => {
-                        debug!("Adding header {} = {}", key, value);
-                        ft.insert(key.into(), AMQPValue::LongString(value.into()))
-                    }
-                    _ => warn!("Ignoring unparsable header value '{}'!", s),
-                };
-                ft
-            })),
-            None => None,
+    pub fn tls(&self, config: &Config) -> common::TlsConfig {
+        let tls = config.tls();
+        common::TlsConfig {
+            ca_cert: self.ca_cert.to_owned().or(tls.ca_cert),
+            client_cert: self.client_cert.to_owned().or(tls.client_cert),
+            client_key: self.client_key.to_owned().or(tls.client_key),
         }
     }
+
+    pub fn headers(&self) -> Option<FieldTable> {
+        self.headers.as_deref().map(common::parse_headers)
+    }
 }
 
-fn main() -> Result<()> {
+fn main() -> anyhow::Result<()> {
     dotenv().ok();
 
     if std::env::var("RUST_LOG").is_err() {
@@ -84,105 +104,113 @@ fn main() -> Result<()> {
     let runtime = Builder::new_current_thread().enable_all().build()?;
 
     let cli = Cli::parse();
+    let config = Config::load()?;
 
     info!("Starting up");
 
-    let addr = cli.url();
-    let exchange = cli.exchange();
-    let routing_key = cli.routing_key();
+    let addr = cli.url(&config);
+    let exchange = cli.exchange(&config)?;
+    let routing_key = cli.routing_key(&config);
     let properties = match cli.headers() {
         Some(headers) => BasicProperties::default().with_headers(headers),
         None => BasicProperties::default(),
     };
+    let properties = match &cli.content_type {
+        Some(content_type) => properties.with_content_type(content_type.to_owned().into()),
+        None => properties,
+    };
+    let correlation_id = cli.correlation_id.to_owned();
+    let tls = cli.tls(&config);
 
     info!("Connecting to {} {} ...", addr, exchange);
 
-    // create connector to rabbitmq server
+    // create connector to rabbitmq server and a publisher-confirms channel on it
     let options = ConnectionProperties::default();
-    let connection = runtime.block_on(async {
-        let connection = Connection::connect(&addr, options)
-            .await
-            .expect("Create connection failure!");
-        debug!(target="connection", state=?connection.status().state());
-        connection
-    });
-
-    // create channel with rabbitmq connection
-    let channel = runtime.block_on(async {
-        let channel = connection
-            .create_channel()
-            .await
-            .expect("Create channel failure!");
-        debug!(target="channel", state=?channel.status().state());
-
-        // set channel to publisher-confirms
-        channel
-            .confirm_select(ConfirmSelectOptions::default())
-            .await
-            .expect("Confirm select failure!");
-        debug!(target="channel", state=?channel.status().state());
-
-        channel
-    });
-
-    let mut counter: i32 = 0;
-
-    while prompt_one(
-        Question::confirm("send")
-            .message("Do you with to send a message?")
-            .default(true)
-            .build(),
-    )
-    .unwrap_or(Answer::Bool(false))
-    .as_bool()
-    .expect("Question::confirm failed to return a bool!")
-    {
-        counter += 1;
-        debug!("Sending Message {} ...", counter);
-
-        let confirmed = runtime.block_on(async {
-            let payload = format!("Hello person #{:02}!", counter);
-            info!("> {}", payload);
-
-            let confirm = channel
-                .basic_publish(
-                    &exchange,
-                    &routing_key,
-                    BasicPublishOptions {
-                        mandatory: true,
-                        ..BasicPublishOptions::default()
-                    },
-                    payload.as_bytes(),
-                    // BasicProperties::default(),
-                    properties.to_owned(),
-                )
-                .await
-                .expect("Basic Publish failure!")
-                .await
-                .expect("Published Confirm failure!");
-
-            if confirm.is_ack() {
-                if let Some(message) = confirm.take_message() {
-                    warn!(
-                        "Messaage rejected with {} {}",
-                        message.reply_code, message.reply_text
-                    );
-                } else {
-                    debug!("Message accepted");
-                    return true;
-                }
-            } else if confirm.is_nack() {
-                warn!("Message not acknowled!")
+    let (_connection, channel) = runtime.block_on(async {
+        let (connection, channel) = common::connect(&addr, options, &tls).await?;
+        common::confirm_select(&channel).await?;
+        Ok::<_, anyhow::Error>((connection, channel))
+    })?;
+
+    if cli.stdin {
+        info!("Streaming messages from stdin");
+        for line in io::stdin().lock().lines() {
+            let line = line.context("failed to read line from stdin")?;
+            let confirmed = runtime.block_on(common::publish(
+                &channel,
+                &exchange,
+                &routing_key,
+                &properties,
+                &correlation_id,
+                line.as_bytes(),
+            ))?;
+            if confirmed {
+                info!("> {} (acked)", line);
             } else {
-                warn!("Unknown message state!")
+                warn!("> {} (nacked)", line);
             }
-            false
-        });
+        }
+    } else if let Some(body) = &cli.body {
+        let confirmed = runtime.block_on(common::publish(
+            &channel,
+            &exchange,
+            &routing_key,
+            &properties,
+            &correlation_id,
+            body.as_bytes(),
+        ))?;
         if confirmed {
             debug!("Message sent");
         } else {
             warn!("message send failed!")
         }
+    } else if let Some(path) = &cli.body_file {
+        let body = fs::read(path).with_context(|| format!("could not read body file `{:?}`", path))?;
+        let confirmed = runtime.block_on(common::publish(
+            &channel,
+            &exchange,
+            &routing_key,
+            &properties,
+            &correlation_id,
+            &body,
+        ))?;
+        if confirmed {
+            debug!("Message sent");
+        } else {
+            warn!("message send failed!")
+        }
+    } else {
+        let mut counter: i32 = 0;
+
+        while prompt_one(
+            Question::confirm("send")
+                .message("Do you with to send a message?")
+                .default(true)
+                .build(),
+        )
+        .unwrap_or(Answer::Bool(false))
+        .as_bool()
+        .expect("Question::confirm failed to return a bool!")
+        {
+            counter += 1;
+            debug!("Sending Message {} ...", counter);
+
+            let payload = format!("Hello person #{:02}!", counter);
+            info!("> {}", payload);
+            let confirmed = runtime.block_on(common::publish(
+                &channel,
+                &exchange,
+                &routing_key,
+                &properties,
+                &correlation_id,
+                payload.as_bytes(),
+            ))?;
+            if confirmed {
+                debug!("Message sent");
+            } else {
+                warn!("message send failed!")
+            }
+        }
     }
     info!("Finishing off and cleaning up");
 
@@ -0,0 +1,59 @@
+use {
+    async_trait::async_trait,
+    std::{collections::HashMap, sync::Arc},
+    tracing::info,
+};
+
+/// A unit of work dispatched from a received delivery.
+///
+/// Implementations own whatever side effect the message triggers; the
+/// consumer only cares whether `run` succeeds so it knows whether to ack or
+/// nack the originating delivery.
+#[async_trait]
+pub trait Job: Send + Sync {
+    async fn run(&self, payload: &[u8]) -> anyhow::Result<()>;
+}
+
+/// Dispatches deliveries to a registered [`Job`] based on routing key.
+///
+/// Falls back to `default` when no job is registered for a given key, so
+/// the consumer can still be pointed at a queue bound to routing keys it
+/// doesn't have a specific handler for.
+#[derive(Default)]
+pub struct Worker {
+    jobs: HashMap<String, Arc<dyn Job>>,
+    default: Option<Arc<dyn Job>>,
+}
+
+impl Worker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(mut self, routing_key: impl Into<String>, job: Arc<dyn Job>) -> Self {
+        self.jobs.insert(routing_key.into(), job);
+        self
+    }
+
+    pub fn with_default(mut self, job: Arc<dyn Job>) -> Self {
+        self.default = Some(job);
+        self
+    }
+
+    pub fn find(&self, routing_key: &str) -> Option<Arc<dyn Job>> {
+        self.jobs.get(routing_key).or(self.default.as_ref()).cloned()
+    }
+}
+
+/// Logs the delivery payload and succeeds, or fails if the payload isn't
+/// valid UTF-8; used when no more specific [`Job`] is registered for a
+/// routing key.
+pub struct LoggingJob;
+
+#[async_trait]
+impl Job for LoggingJob {
+    async fn run(&self, payload: &[u8]) -> anyhow::Result<()> {
+        info!("> {}", std::str::from_utf8(payload)?);
+        Ok(())
+    }
+}
@@ -1,36 +1,85 @@
+mod job;
+
 use {
+    anyhow::Context,
     clap::Parser,
+    common::{retry_count, Config},
     dotenv::dotenv,
+    job::{Job, LoggingJob, Worker},
     joinery::JoinableIterator,
     lapin::{
         message::DeliveryResult,
-        options::{BasicAckOptions, BasicConsumeOptions},
+        options::{BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions},
         types::{FieldTable, LongString},
-        Connection, ConnectionProperties, Result,
+        ConnectionProperties,
+    },
+    std::{
+        path::PathBuf,
+        sync::Arc,
+        time::{SystemTime, UNIX_EPOCH},
     },
-    std::str,
-    tokio::signal,
-    tracing::{debug, error, info, warn},
+    tokio::{signal, sync::Semaphore},
+    tracing::{error, info, warn},
 };
 
 #[derive(Parser, Debug)]
 struct Cli {
-    /// URL of the RabbitMQ server
-    #[arg(
-        name = "url",
-        long,
-        short,
-        env = "AMQP_ADDR",
-        default_value_t = String::from("amqp://localhost:5672")
-    )]
-    url: String,
-    /// Queue to subscribe to
-    #[arg(name = "queue", long, short, env = "SUB_QUEUE")]
-    queue: String,
+    /// URL of the RabbitMQ server, overrides the AMQP_ADDR config
+    #[arg(name = "url", long, short)]
+    url: Option<String>,
+    /// Queue to subscribe to, overrides the `queue` config
+    #[arg(name = "queue", long, short)]
+    queue: Option<String>,
+    /// Maximum number of deliveries processed concurrently, overrides the
+    /// `concurrency` config
+    #[arg(name = "concurrency", long, short)]
+    concurrency: Option<usize>,
+    /// PEM file of a custom CA to trust for amqps:// connections, overrides
+    /// the `ca_cert` config
+    #[arg(name = "ca-cert", long)]
+    ca_cert: Option<PathBuf>,
+    /// Client certificate PEM for mutual TLS, overrides the `client_cert`
+    /// config; requires --client-key
+    #[arg(name = "client-cert", long)]
+    client_cert: Option<PathBuf>,
+    /// Client private key PEM for mutual TLS, overrides the `client_key`
+    /// config; requires --client-cert
+    #[arg(name = "client-key", long)]
+    client_key: Option<PathBuf>,
+    /// Dead-letter exchange to declare the queue with
+    #[arg(name = "dlx", long)]
+    dlx: Option<String>,
+    /// Dead-letter routing key to declare the queue with, used alongside --dlx
+    #[arg(name = "dlx-routing-key", long)]
+    dlx_routing_key: Option<String>,
+    /// Deliveries that fail more than this many times are dead-lettered
+    /// instead of requeued
+    #[arg(name = "max-retries", long, default_value_t = 5)]
+    max_retries: u64,
+}
+
+impl Cli {
+    fn tls(&self, config: &Config) -> common::TlsConfig {
+        let tls = config.tls();
+        common::TlsConfig {
+            ca_cert: self.ca_cert.to_owned().or(tls.ca_cert),
+            client_cert: self.client_cert.to_owned().or(tls.client_cert),
+            client_key: self.client_key.to_owned().or(tls.client_key),
+        }
+    }
+}
+
+/// Builds the routing-key -> [`Job`] registry for this consumer.
+///
+/// There's only the demo [`LoggingJob`] today, registered as the fallback
+/// so any routing key still gets handled; real jobs get `register`ed here
+/// as they're added.
+fn worker() -> Worker {
+    Worker::new().with_default(Arc::new(LoggingJob))
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> anyhow::Result<()> {
     dotenv().ok();
 
     if std::env::var("RUST_LOG").is_err() {
@@ -39,10 +88,17 @@ async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
     let cli = Cli::parse();
+    let config = Config::load()?;
 
     info!("Starting up");
-    let addr = &cli.url;
-    let queue = &cli.queue;
+    let addr = cli.url.clone().unwrap_or_else(|| config.amqp_addr.clone());
+    let queue = cli
+        .queue
+        .clone()
+        .or_else(|| config.queue.clone())
+        .context("no queue given; pass --queue or set the `queue` config")?;
+    let concurrency = cli.concurrency.unwrap_or(config.concurrency);
+    let tls = cli.tls(&config);
     info!("Connecting to {} {} ...", addr, queue);
 
     let options = ConnectionProperties::default()
@@ -51,18 +107,20 @@ async fn main() -> Result<()> {
         .with_executor(tokio_executor_trait::Tokio::current())
         .with_reactor(tokio_reactor_trait::Tokio);
 
-    let connection = Connection::connect(addr, options)
-        .await
-        .expect("Create connection failure!");
-    debug!(target="connection", state=?connection.status().state());
+    let (connection, channel) = common::connect(&addr, options, &tls).await?;
+
+    info!("Connected to server!");
 
-    let channel = connection
-        .create_channel()
+    // Cap how many unacked deliveries the broker will push to this channel
+    // at once; without this the local `Semaphore` below only throttles how
+    // many are *processed* concurrently; the broker would still be free to
+    // flood the client with an unbounded number of unacked messages.
+    channel
+        .basic_qos(config.prefetch, BasicQosOptions::default())
         .await
-        .expect("Create channel failure!");
-    debug!(target="channel", state=?channel.status().state());
+        .context("failed to set channel QoS")?;
 
-    info!("Connected to server!");
+    common::declare_queue_with_dlx(&channel, &queue, &cli.dlx, &cli.dlx_routing_key).await?;
 
     let consumer = channel
         .basic_consume(
@@ -72,47 +130,146 @@ async fn main() -> Result<()> {
             FieldTable::default(),
         )
         .await?;
-    info!("Subscribed to queue {}!", cli.queue);
-
-    consumer.set_delegate(move |delivery: DeliveryResult| async move {
-        let delivery = match delivery {
-            // Carries the delivery alongside its channel
-            Ok(Some(delivery)) => delivery,
-            // The consumer got canceled
-            Ok(None) => {
-                return;
-            }
-            // Carries the error and is always followed by Ok(None)
-            Err(error) => {
-                warn!("Failed to consume queue message {}", error);
-                return;
-            }
-        };
-
-        info!(
-            "\nReceived {} :: {:?}\n{}",
-            &delivery.routing_key,
-            match &delivery.properties.headers().as_ref() {
-                Some(headers) => headers
-                    .into_iter()
-                    .map(|(k, v)| {
-                        format!(
-                            "{}={}",
-                            k,
-                            v.as_long_string().unwrap_or(&LongString::from(""))
-                        )
-                    })
-                    .join_with(", ")
-                    .to_string(),
-                None => String::from(""),
-            },
-            str::from_utf8(&delivery.data[..]).unwrap()
-        );
-
-        delivery
-            .ack(BasicAckOptions::default())
-            .await
-            .expect("Message acknowledgement failed!");
+    info!("Subscribed to queue {}!", queue);
+
+    let worker = Arc::new(worker());
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let max_retries = cli.max_retries;
+    let requeue_channel = channel.clone();
+    let requeue_queue = queue.clone();
+
+    consumer.set_delegate(move |delivery: DeliveryResult| {
+        let worker = worker.clone();
+        let semaphore = semaphore.clone();
+        let channel = requeue_channel.clone();
+        let queue = requeue_queue.clone();
+        async move {
+            let delivery = match delivery {
+                // Carries the delivery alongside its channel
+                Ok(Some(delivery)) => delivery,
+                // The consumer got canceled
+                Ok(None) => {
+                    return;
+                }
+                // Carries the error and is always followed by Ok(None)
+                Err(error) => {
+                    warn!("Failed to consume queue message {}", error);
+                    return;
+                }
+            };
+
+            // Bound how many deliveries are in flight at once; acquiring
+            // blocks the delegate (and therefore further dispatch) once
+            // `concurrency` jobs are already running.
+            let permit = match semaphore.acquire_owned().await {
+                Ok(permit) => permit,
+                Err(error) => {
+                    error!("Worker semaphore closed: {}", error);
+                    return;
+                }
+            };
+
+            tokio::spawn(async move {
+                let _permit = permit;
+                let routing_key = common::routing_key(&delivery);
+
+                let latency = delivery.properties.timestamp().map(|sent| {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|duration| duration.as_secs())
+                        .unwrap_or(0);
+                    now.saturating_sub(sent)
+                });
+
+                info!(
+                    "\nReceived {} :: message_id={} correlation_id={} latency={}s :: {:?}\n{}",
+                    routing_key,
+                    delivery
+                        .properties
+                        .message_id()
+                        .as_ref()
+                        .map(|id| id.as_str())
+                        .unwrap_or(""),
+                    delivery
+                        .properties
+                        .correlation_id()
+                        .as_ref()
+                        .map(|id| id.as_str())
+                        .unwrap_or(""),
+                    latency
+                        .map(|secs| secs.to_string())
+                        .unwrap_or_else(|| String::from("?")),
+                    match &delivery.properties.headers().as_ref() {
+                        Some(headers) => headers
+                            .into_iter()
+                            .map(|(k, v)| {
+                                format!(
+                                    "{}={}",
+                                    k,
+                                    v.as_long_string().unwrap_or(&LongString::from(""))
+                                )
+                            })
+                            .join_with(", ")
+                            .to_string(),
+                        None => String::from(""),
+                    },
+                    String::from_utf8_lossy(&delivery.data[..])
+                );
+
+                let job = worker.find(&routing_key);
+                let result = match job {
+                    Some(job) => job.run(&delivery.data).await,
+                    None => {
+                        warn!("No job registered for routing key {}", routing_key);
+                        Ok(())
+                    }
+                };
+
+                match result {
+                    Ok(()) => {
+                        if let Err(error) = delivery.ack(BasicAckOptions::default()).await {
+                            error!("Failed to ack message: {}", error);
+                        }
+                    }
+                    Err(error) => {
+                        // Retry until the message has been retried
+                        // `max_retries` times, then give up on it for good
+                        // so it routes to the DLX instead of looping
+                        // forever. `x-death` only gets stamped once a
+                        // message has actually passed through a
+                        // dead-letter exchange, which a plain
+                        // `requeue: true` nack never does, so the retry
+                        // count is tracked ourselves via a custom header
+                        // instead.
+                        let retries = retry_count(&delivery);
+                        let requeue = retries < max_retries;
+                        warn!(
+                            "Job failed for {} ({}), attempt {}/{}, {}",
+                            routing_key,
+                            error,
+                            retries + 1,
+                            max_retries + 1,
+                            if requeue { "requeuing" } else { "dead-lettering" }
+                        );
+                        if requeue {
+                            if let Err(error) =
+                                common::requeue_with_retry(&channel, &queue, &delivery, retries + 1).await
+                            {
+                                error!("Failed to requeue message: {}", error);
+                            }
+                        } else if let Err(error) = delivery
+                            .nack(BasicNackOptions {
+                                requeue: false,
+                                ..BasicNackOptions::default()
+                            })
+                            .await
+                        {
+                            error!("Failed to nack message: {}", error);
+                        }
+                    }
+                }
+            });
+        }
     });
     info!("Listening for messages");
 